@@ -0,0 +1,290 @@
+//! Arithmetic expansion: `$(( expression ))`.
+//!
+//! A small tokenizer plus a precedence-climbing (Pratt) parser and evaluator for the
+//! usual C-like integer arithmetic bash supports inside `$(( ... ))`. Bare identifiers
+//! are resolved through the same `ValueProvider` used for parameter expansion,
+//! defaulting to `0` when unset, just like bash.
+
+use crate::{Error, ValueProvider};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Plus,
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    Int(i64),
+    Var(String),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(Box<Expression>, BinaryOp, Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Ident(String),
+    LParen,
+    RParen,
+    Binary(BinaryOp),
+    Not,
+    BitNot,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let c = input[i..].chars().next().expect("i is a char boundary");
+        let rest = &input[i..];
+        match c {
+            c if c.is_whitespace() => i += c.len_utf8(),
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '0'..='9' => {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                let value: i64 = rest[..len].parse().map_err(|_| Error::InvalidValue)?;
+                tokens.push(Token::Int(value));
+                i += len;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let len = rest
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                tokens.push(Token::Ident(rest[..len].to_string()));
+                i += len;
+            }
+            '*' if rest.as_bytes().get(1) == Some(&b'*') => {
+                tokens.push(Token::Binary(BinaryOp::Pow));
+                i += 2;
+            }
+            '<' if rest.as_bytes().get(1) == Some(&b'<') => {
+                tokens.push(Token::Binary(BinaryOp::Shl));
+                i += 2;
+            }
+            '>' if rest.as_bytes().get(1) == Some(&b'>') => {
+                tokens.push(Token::Binary(BinaryOp::Shr));
+                i += 2;
+            }
+            '+' => {
+                tokens.push(Token::Binary(BinaryOp::Add));
+                i += 1;
+            }
+            '-' => {
+                tokens.push(Token::Binary(BinaryOp::Sub));
+                i += 1;
+            }
+            '*' => {
+                tokens.push(Token::Binary(BinaryOp::Mul));
+                i += 1;
+            }
+            '/' => {
+                tokens.push(Token::Binary(BinaryOp::Div));
+                i += 1;
+            }
+            '%' => {
+                tokens.push(Token::Binary(BinaryOp::Rem));
+                i += 1;
+            }
+            '&' => {
+                tokens.push(Token::Binary(BinaryOp::And));
+                i += 1;
+            }
+            '|' => {
+                tokens.push(Token::Binary(BinaryOp::Or));
+                i += 1;
+            }
+            '^' => {
+                tokens.push(Token::Binary(BinaryOp::Xor));
+                i += 1;
+            }
+            '!' => {
+                tokens.push(Token::Not);
+                i += 1;
+            }
+            '~' => {
+                tokens.push(Token::BitNot);
+                i += 1;
+            }
+            _ => return Err(Error::InvalidValue),
+        }
+    }
+    Ok(tokens)
+}
+
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Pow => 7,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Shl | BinaryOp::Shr => 4,
+        BinaryOp::And => 3,
+        BinaryOp::Xor => 2,
+        BinaryOp::Or => 1,
+    }
+}
+
+fn right_associative(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Pow)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// precedence-climbing entry point; `min_prec` is the lowest-precedence binary
+    /// operator this call is allowed to consume.
+    fn parse_expression(&mut self, min_prec: u8) -> Result<Expression, Error> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(Token::Binary(op)) = self.peek() {
+            let prec = precedence(op);
+            if prec < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let next_min = if right_associative(op) { prec } else { prec + 1 };
+            let rhs = self.parse_expression(next_min)?;
+            lhs = Expression::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, Error> {
+        match self.peek() {
+            Some(Token::Binary(BinaryOp::Add)) => {
+                self.pos += 1;
+                Ok(Expression::Unary(UnaryOp::Plus, Box::new(self.parse_unary()?)))
+            }
+            Some(Token::Binary(BinaryOp::Sub)) => {
+                self.pos += 1;
+                Ok(Expression::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)))
+            }
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Expression::Unary(UnaryOp::Not, Box::new(self.parse_unary()?)))
+            }
+            Some(Token::BitNot) => {
+                self.pos += 1;
+                Ok(Expression::Unary(UnaryOp::BitNot, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_atom(),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression, Error> {
+        match self.next() {
+            Some(Token::Int(value)) => Ok(Expression::Int(value)),
+            Some(Token::Ident(name)) => Ok(Expression::Var(name)),
+            Some(Token::LParen) => {
+                let expr = self.parse_expression(0)?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(Error::InvalidValue),
+                }
+            }
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
+fn eval<T: ValueProvider>(provider: &T, expr: &Expression) -> Result<i64, Error> {
+    match expr {
+        Expression::Int(value) => Ok(*value),
+        Expression::Var(name) => match provider.get(name) {
+            Ok(value) => value.trim().parse().map_err(|_| Error::InvalidValue),
+            Err(Error::NotPresent) => Ok(0),
+            Err(error) => Err(error),
+        },
+        Expression::Unary(op, inner) => {
+            let value = eval(provider, inner)?;
+            Ok(match op {
+                UnaryOp::Plus => value,
+                UnaryOp::Neg => value.wrapping_neg(),
+                UnaryOp::Not => i64::from(value == 0),
+                UnaryOp::BitNot => !value,
+            })
+        }
+        Expression::Binary(lhs, op, rhs) => {
+            let lhs = eval(provider, lhs)?;
+            let rhs = eval(provider, rhs)?;
+            match op {
+                BinaryOp::Add => Ok(lhs.wrapping_add(rhs)),
+                BinaryOp::Sub => Ok(lhs.wrapping_sub(rhs)),
+                BinaryOp::Mul => Ok(lhs.wrapping_mul(rhs)),
+                BinaryOp::Div | BinaryOp::Rem if rhs == 0 => Err(Error::DivisionByZero),
+                BinaryOp::Div => Ok(lhs.wrapping_div(rhs)),
+                BinaryOp::Rem => Ok(lhs.wrapping_rem(rhs)),
+                BinaryOp::Pow => Ok(pow(lhs, rhs)),
+                BinaryOp::Shl => Ok(lhs.wrapping_shl(rhs as u32)),
+                BinaryOp::Shr => Ok(lhs.wrapping_shr(rhs as u32)),
+                BinaryOp::And => Ok(lhs & rhs),
+                BinaryOp::Or => Ok(lhs | rhs),
+                BinaryOp::Xor => Ok(lhs ^ rhs),
+            }
+        }
+    }
+}
+
+fn pow(base: i64, exp: i64) -> i64 {
+    if exp < 0 {
+        return 0;
+    }
+    let mut result = 1i64;
+    let mut base = base;
+    let mut exp = exp as u64;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// parses and evaluates the inside of a `$(( ... ))`, returning its decimal string.
+pub(crate) fn expand<T: ValueProvider>(provider: &T, input: &str) -> Result<String, Error> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens: &tokens, pos: 0 };
+    let expr = parser.parse_expression(0)?;
+    if parser.pos != tokens.len() {
+        return Err(Error::InvalidValue);
+    }
+    eval(provider, &expr).map(|value| value.to_string())
+}
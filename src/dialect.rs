@@ -0,0 +1,110 @@
+//! Declarative dialect definitions, loadable from TOML via `serde`.
+//!
+//! A [`DialectSpec`] is the serializable shape of a [`Config`]: instead of hand-writing
+//! a `Config::bash()`-style constructor in Rust, a dialect can be described as data and
+//! compiled at load time. [`load_dialects`] reads a table of named specs from a TOML
+//! document, so downstream tools can register custom `%VAR%` / `{{var}}` / `$(var)`
+//! syntaxes without recompiling.
+
+use std::collections::HashMap;
+
+use fancy_regex::Regex;
+use thiserror::Error;
+
+use crate::{default_define_pattern, Config, Grammar, OnNotPresent};
+
+/// the serializable description of a [`Config`]. `open`/`close` bound a variable
+/// reference (e.g. `${` / `}` for bash, `%` / `%` for cmd's `%NAME%`); when `close` is
+/// absent the reference is `open` directly followed by the name, with no closing
+/// delimiter (docker's `$NAME`).
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct DialectSpec {
+    #[serde(default)]
+    pub open: String,
+    #[serde(default)]
+    pub close: Option<String>,
+    /// regex fragment for a variable name. Defaults to `\w+`.
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// when true, compiles to the full nested/operator-aware bash grammar instead of a
+    /// flat single-capture regex; `open`/`close`/`name` are ignored, since that grammar
+    /// is fixed to `$name` / `${...}`.
+    #[serde(default)]
+    pub operators: bool,
+    #[serde(default)]
+    pub on_not_present: OnNotPresent,
+    #[serde(default)]
+    pub escape: Vec<(String, String)>,
+}
+
+fn default_name() -> String {
+    r"\w+".to_string()
+}
+
+#[derive(Error, Debug)]
+pub enum LoadError {
+    // both payloads are boxed: `toml::de::Error` and `fancy_regex::Error` are each
+    // large enough on their own to bloat every `Result<_, LoadError>`.
+    #[error("invalid dialect toml: {0}")]
+    Toml(#[from] Box<toml::de::Error>),
+    #[error("dialect {name:?} has an invalid pattern: {source}")]
+    Pattern {
+        name: String,
+        source: Box<fancy_regex::Error>,
+    },
+}
+
+impl DialectSpec {
+    /// compiles this spec into a [`Config`]. `name` is only used to label a `Pattern` error.
+    pub fn compile(&self, name: &str) -> Result<Config, LoadError> {
+        if self.operators {
+            return Ok(Config {
+                on_not_present: self.on_not_present.clone(),
+                escape: self.escape.clone(),
+                ..Config::bash()
+            });
+        }
+
+        let close = self.close.as_deref().unwrap_or("");
+        let pattern = format!(
+            r"(?<!\\){}(?<name>{}){}",
+            regex::escape(&self.open),
+            self.name,
+            regex::escape(close)
+        );
+        let pattern = Regex::new(&pattern).map_err(|source| LoadError::Pattern {
+            name: name.to_string(),
+            source: Box::new(source),
+        })?;
+
+        Ok(Config {
+            pattern: Some(pattern),
+            on_not_present: self.on_not_present.clone(),
+            escape: self.escape.clone(),
+            grammar: Grammar::Flat,
+            allow_inline_defines: false,
+            define_pattern: default_define_pattern(),
+        })
+    }
+}
+
+/// loads a table of named dialects from TOML, e.g.
+///
+/// ```toml
+/// [mustache]
+/// open = "{{"
+/// close = "}}"
+///
+/// [bash]
+/// operators = true
+/// ```
+pub fn load_dialects(toml: &str) -> Result<HashMap<String, Config>, LoadError> {
+    let specs: HashMap<String, DialectSpec> = toml::from_str(toml).map_err(Box::new)?;
+    specs
+        .into_iter()
+        .map(|(name, spec)| {
+            let config = spec.compile(&name)?;
+            Ok((name, config))
+        })
+        .collect()
+}
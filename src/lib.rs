@@ -4,7 +4,12 @@ use fancy_regex::{Captures, Regex};
 use lazy_static::lazy_static;
 use thiserror::Error;
 
-#[derive(Error, Debug, Clone, Copy)]
+mod arithmetic;
+pub mod dialect;
+pub mod match_table;
+mod parser;
+
+#[derive(Error, Debug, Clone)]
 pub enum Error {
     #[error("could not find varible")]
     NotPresent,
@@ -14,20 +19,55 @@ pub enum Error {
 
     #[error("varible contains a bad value")]
     InvalidValue,
+
+    #[error("division or modulo by zero in arithmetic expansion")]
+    DivisionByZero,
+
+    /// `${var:?message}` / `${var?message}` when `var` is unset (or empty, for the
+    /// colon form): carries the message from the template itself, rather than a fixed
+    /// string.
+    #[error("{0}")]
+    Required(Box<str>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub enum OnNotPresent {
     Error,
     Passthrough,
     Default(String),
 }
 
+impl Default for OnNotPresent {
+    fn default() -> Self {
+        OnNotPresent::Default(String::new())
+    }
+}
+
+/// which engine `ValueProvider::substitute` uses to walk a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grammar {
+    /// `$name` / `${name}`, with the full set of POSIX/bash parameter-expansion
+    /// operators and arbitrary nesting. Walked by the recursive-descent [`parser`]
+    /// rather than `Config::pattern`, which is unused for this grammar.
+    Bash,
+    /// a single flat match of `Config::pattern`, with no operators or nesting.
+    Flat,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub pattern: Regex,
+    /// the flat single-capture regex `Grammar::Flat` walks. Unused (and left `None`)
+    /// for `Grammar::Bash`, which walks its input itself via the recursive-descent
+    /// `parser` instead.
+    pub pattern: Option<Regex>,
     pub on_not_present: OnNotPresent,
-    pub escape: Vec<(&'static str, &'static str)>,
+    pub escape: Vec<(String, String)>,
+    pub grammar: Grammar,
+    /// when set, lines matching `define_pattern` are stripped from the template and
+    /// folded into an overlay that takes precedence over the `ValueProvider` for the
+    /// rest of the expansion.
+    pub allow_inline_defines: bool,
+    pub define_pattern: Regex,
 }
 
 struct Pattern {
@@ -35,30 +75,41 @@ struct Pattern {
     close: Option<String>,
 }
 
+/// the default `define_pattern`: a `#define NAME = VALUE` directive on its own line,
+/// with an optionally double-quoted value.
+fn default_define_pattern() -> Regex {
+    Regex::new(r#"^#define\s+(?<name>\w+)\s*=\s*(?<value>.*)$"#).unwrap()
+}
+
 impl Config {
     fn bash() -> Config {
         Config {
             on_not_present: OnNotPresent::Default("".to_string()),
-            pattern: Regex::new(
-                // 1st ?: -> ?|
-                r"(?<!\\)\$(?:(?<name>\w+)|(?:{(?<name>\w+)(?:-(?<default>\w+))?}))",
-            )
-            .unwrap(),
-            escape: vec![("\\$", "$")],
+            pattern: None,
+            escape: vec![("\\$".to_string(), "$".to_string())],
+            grammar: Grammar::Bash,
+            allow_inline_defines: false,
+            define_pattern: default_define_pattern(),
         }
     }
     fn docker() -> Config {
         Config {
             on_not_present: OnNotPresent::Default("".to_string()),
-            pattern: Regex::new(r"(?<!\$)\$(?<name>\w+)").unwrap(),
-            escape: vec![("$", "$$")],
+            pattern: Some(Regex::new(r"(?<!\$)\$(?<name>\w+)").unwrap()),
+            escape: vec![("$".to_string(), "$$".to_string())],
+            grammar: Grammar::Flat,
+            allow_inline_defines: false,
+            define_pattern: default_define_pattern(),
         }
     }
     fn cmd() -> Config {
         Config {
             on_not_present: OnNotPresent::Default("".to_string()),
-            pattern: Regex::new(r"(?<!\%)%(?<name>\w+)%").unwrap(),
-            escape: vec![("$", "$$")],
+            pattern: Some(Regex::new(r"(?<!\%)%(?<name>\w+)%").unwrap()),
+            escape: vec![("$".to_string(), "$$".to_string())],
+            grammar: Grammar::Flat,
+            allow_inline_defines: false,
+            define_pattern: default_define_pattern(),
         }
     }
 }
@@ -66,64 +117,313 @@ lazy_static! {
     static ref BASH: Config = Config::bash();
 }
 
-trait ValueProivder {
-    fn get(&self, name: impl AsRef<str>) -> Result<Cow<str>, Error>;
+/// whether a variable should be treated as "unset" (bare operators) vs "unset or empty string"
+/// (colon operators) per POSIX parameter expansion rules.
+fn is_unset(value: &Result<Cow<str>, Error>) -> bool {
+    matches!(value, Err(Error::NotPresent))
+}
 
-    fn substitute<'t>(&self, config: &'t Config, on: &'t str) -> Result<Cow<'t, str>, Error> {
-        let mut errors = Vec::new();
+fn is_unset_or_empty(value: &Result<Cow<str>, Error>) -> bool {
+    match value {
+        Err(Error::NotPresent) => true,
+        Ok(v) => v.is_empty(),
+        _ => false,
+    }
+}
 
-        let replacer = |captures: &Captures| {
-            let captures: Vec<_> = captures.iter().flatten().collect();
-            let all = captures.get(0);
-            let name = captures
-                .get(1) //name
-                .expect("invalid regex doesn't capture name")
-                .as_str();
-            let default = captures.get(2);
-            let value = self.get(name);
-            let (error, result) = match value {
-                Ok(result) => (None, result),
-                Err(error) => match error {
-                    Error::NotPresent => {
-                        if let Some(default) = default {
-                            //(None, default)
-                            (None, Cow::Owned(default.as_str().to_string()))
-                        } else {
-                            match &config.on_not_present {
-                                OnNotPresent::Error => (Some(Error::NotPresent), Cow::Borrowed("")),
-                                OnNotPresent::Passthrough => (
-                                    None,
-                                    Cow::Owned(
-                                        all
-                                            .expect("match didn't match")
-                                            .as_str()
-                                            .to_string(),
-                                    ),
-                                ),
-                                OnNotPresent::Default(_default) => {
-                                    //default.as_str()
-                                    (None, Cow::Borrowed(""))
-                                }
-                            }
+/// translate a (very) small glob dialect (`*` and `?`) into a regex fragment.
+fn glob_to_regex_fragment(pattern: &str, out: &mut String, star: &str) {
+    for c in pattern.chars() {
+        match c {
+            '*' => out.push_str(star),
+            '?' => out.push('.'),
+            other => out.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+}
+
+fn glob_full_regex(pattern: &str, greedy: bool) -> regex::Regex {
+    let mut out = String::from("^");
+    glob_to_regex_fragment(pattern, &mut out, if greedy { ".*" } else { ".*?" });
+    out.push('$');
+    regex::Regex::new(&out).expect("glob pattern produced an invalid regex")
+}
+
+fn glob_search_regex(pattern: &str) -> regex::Regex {
+    let mut out = String::new();
+    glob_to_regex_fragment(pattern, &mut out, ".*");
+    regex::Regex::new(&out).expect("glob pattern produced an invalid regex")
+}
+
+/// `${var#pattern}` / `${var##pattern}`: strip the shortest/longest matching prefix.
+fn strip_prefix_glob(value: &str, pattern: &str, longest: bool) -> String {
+    let re = glob_full_regex(pattern, longest);
+    let mut ends: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    ends.push(value.len());
+    let found = if longest {
+        ends.into_iter().rev().find(|&end| re.is_match(&value[..end]))
+    } else {
+        ends.into_iter().find(|&end| re.is_match(&value[..end]))
+    };
+    match found {
+        Some(end) => value[end..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// `${var%pattern}` / `${var%%pattern}`: strip the shortest/longest matching suffix.
+fn strip_suffix_glob(value: &str, pattern: &str, longest: bool) -> String {
+    let re = glob_full_regex(pattern, longest);
+    let mut starts: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    starts.push(value.len());
+    let found = if longest {
+        starts.into_iter().find(|&start| re.is_match(&value[start..]))
+    } else {
+        starts.into_iter().rev().find(|&start| re.is_match(&value[start..]))
+    };
+    match found {
+        Some(start) => value[..start].to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// splits `pattern/replacement` on the first `/`, as used by `${var/pat/repl}`. A
+/// backslash does not escape the `/`.
+fn split_pattern_and_replacement(arg: &str) -> (&str, &str) {
+    match arg.find('/') {
+        Some(index) => (&arg[..index], &arg[index + 1..]),
+        None => (arg, ""),
+    }
+}
+
+/// `${var/pattern/replacement}` / `${var//pattern/replacement}`: replace first/all matches.
+fn replace_glob(value: &str, arg: &str, all: bool) -> String {
+    let (pattern, replacement) = split_pattern_and_replacement(arg);
+    let re = glob_search_regex(pattern);
+    let replacement = regex::NoExpand(replacement);
+    if all {
+        re.replace_all(value, replacement).into_owned()
+    } else {
+        re.replacen(value, 1, replacement).into_owned()
+    }
+}
+
+/// `${var:offset}` / `${var:offset:length}`: substring, with bash's negative-offset-from-end rule.
+fn substring(value: &str, arg: &str) -> String {
+    let mut parts = arg.splitn(2, ':');
+    let offset: i64 = parts.next().unwrap_or("").trim().parse().unwrap_or(0);
+    let length = parts.next();
+
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+    let start = if offset < 0 { (len + offset).max(0) } else { offset.min(len) };
+    let end = match length {
+        Some(length) => {
+            let length: i64 = length.trim().parse().unwrap_or(0);
+            if length < 0 {
+                (len + length).max(start)
+            } else {
+                start.saturating_add(length).min(len)
+            }
+        }
+        None => len,
+    };
+
+    chars[start as usize..end as usize].iter().collect()
+}
+
+/// resolves a single `${name<op><arg>}` parameter expansion once `name` has already been looked up.
+fn expand_operator(
+    config: &Config,
+    value: Result<Cow<str>, Error>,
+    op: &str,
+    arg: &str,
+) -> (Option<Error>, Cow<'static, str>) {
+    // only the colon variants treat a present-but-empty value the same as unset.
+    let colon = op.starts_with(':') && op.len() > 1;
+
+    match op {
+        ":-" | "-" => {
+            let missing = if colon { is_unset_or_empty(&value) } else { is_unset(&value) };
+            if missing {
+                (None, Cow::Owned(arg.to_string()))
+            } else {
+                match value {
+                    Ok(v) => (None, Cow::Owned(v.into_owned())),
+                    Err(error) => (Some(error), Cow::Borrowed("")),
+                }
+            }
+        }
+        ":+" | "+" => {
+            let missing = if colon { is_unset_or_empty(&value) } else { is_unset(&value) };
+            match value {
+                Err(error) if !matches!(error, Error::NotPresent) => (Some(error), Cow::Borrowed("")),
+                _ if missing => (None, Cow::Borrowed("")),
+                _ => (None, Cow::Owned(arg.to_string())),
+            }
+        }
+        ":?" | "?" => {
+            let missing = if colon { is_unset_or_empty(&value) } else { is_unset(&value) };
+            if missing {
+                (Some(Error::Required(arg.into())), Cow::Borrowed(""))
+            } else {
+                match value {
+                    Ok(v) => (None, Cow::Owned(v.into_owned())),
+                    Err(error) => (Some(error), Cow::Borrowed("")),
+                }
+            }
+        }
+        "#" | "##" => with_value_or_default(config, value, |v| strip_prefix_glob(v, arg, op == "##")),
+        "%" | "%%" => with_value_or_default(config, value, |v| strip_suffix_glob(v, arg, op == "%%")),
+        "/" | "//" => with_value_or_default(config, value, |v| replace_glob(v, arg, op == "//")),
+        ":" => with_value_or_default(config, value, |v| substring(v, arg)),
+        _ => (Some(Error::InvalidName), Cow::Borrowed("")),
+    }
+}
+
+/// applies `f` to a resolved value, falling back to `config.on_not_present` when it is missing.
+fn with_value_or_default(
+    config: &Config,
+    value: Result<Cow<str>, Error>,
+    f: impl FnOnce(&str) -> String,
+) -> (Option<Error>, Cow<'static, str>) {
+    match value {
+        Ok(v) => (None, Cow::Owned(f(&v))),
+        Err(Error::NotPresent) => match &config.on_not_present {
+            OnNotPresent::Error => (Some(Error::NotPresent), Cow::Borrowed("")),
+            OnNotPresent::Passthrough => (None, Cow::Borrowed("")),
+            OnNotPresent::Default(default) => (None, Cow::Owned(default.clone())),
+        },
+        Err(error) => (Some(error), Cow::Borrowed("")),
+    }
+}
+
+/// a source of values for parameter expansion. Object-safe, so sources can be composed
+/// behind a `Box<dyn ValueProvider>` (see [`Chain`]); `substitute` is only available
+/// when `Self: Sized`, since it dispatches into the generic [`parser`]/flat-regex code.
+pub trait ValueProvider {
+    fn get(&self, name: &str) -> Result<Cow<str>, Error>;
+
+    fn substitute<'t>(&self, config: &'t Config, on: &'t str) -> Result<Cow<'t, str>, Error>
+    where
+        Self: Sized,
+    {
+        if !config.allow_inline_defines {
+            return dispatch(self, config, on);
+        }
+
+        // pre-pass: pull `#define NAME = VALUE` lines out of the template and layer
+        // them in front of `self` for the rest of the expansion.
+        let (stripped, overlay) = extract_defines(config, on);
+        let layered = WithOverlay { overlay: &overlay, inner: self };
+        dispatch(&layered, config, &stripped).map(|result| Cow::Owned(result.into_owned()))
+    }
+}
+
+/// the actual grammar dispatch, factored out of the trait method so the inline-defines
+/// pre-pass in `substitute` can run it again over a provider layered with the defines.
+fn dispatch<'o, T: ValueProvider>(
+    provider: &T,
+    config: &Config,
+    on: &'o str,
+) -> Result<Cow<'o, str>, Error> {
+    match config.grammar {
+        // the recursive parser needs brace-matching a flat regex can't express, so it
+        // walks `on` itself; `config.pattern` is `None` and unused for this grammar.
+        Grammar::Bash => {
+            if !on.contains('$') {
+                return Ok(Cow::Borrowed(on));
+            }
+            let mut errors = Vec::new();
+            let replaced = parser::expand(provider, config, on, &mut errors);
+            if let Some(error) = errors.pop() {
+                Err(error)
+            } else {
+                Ok(Cow::Owned(replaced))
+            }
+        }
+        Grammar::Flat => {
+            let pattern = config
+                .pattern
+                .as_ref()
+                .expect("Grammar::Flat requires Config::pattern");
+            let mut errors = Vec::new();
+            let replacer = |captures: &Captures| -> Cow<str> {
+                let name = captures
+                    .name("name")
+                    .expect("invalid regex doesn't capture name")
+                    .as_str();
+
+                match provider.get(name) {
+                    Ok(result) => result,
+                    Err(Error::NotPresent) => match &config.on_not_present {
+                        OnNotPresent::Error => {
+                            errors.push(Error::NotPresent);
+                            Cow::Borrowed("")
                         }
+                        OnNotPresent::Passthrough => Cow::Owned(
+                            captures
+                                .get(0)
+                                .expect("match didn't match")
+                                .as_str()
+                                .to_string(),
+                        ),
+                        OnNotPresent::Default(default) => Cow::Owned(default.clone()),
+                    },
+                    Err(error) => {
+                        errors.push(error);
+                        Cow::Borrowed("")
                     }
-                    error => (Some(error), Cow::Borrowed("")),
-                },
+                }
             };
-
-            if let Some(error) = error {
-                errors.push(error);
+            let replaced = pattern.replace_all(on, replacer);
+            if let Some(error) = errors.pop() {
+                Err(error)
+            } else {
+                Ok(replaced)
             }
-            result /*
-                   let value = value.unwrap();
-                   value
-                           */
-        };
-        let replaced = config.pattern.replace_all(on, replacer);
-        if let Some(error) = errors.pop() {
-            Err(error)
-        } else {
-            Ok(replaced)
+        }
+    }
+}
+
+/// strips `config.define_pattern` lines out of `on`, returning the remaining template
+/// text and the name/value pairs those lines declared.
+fn extract_defines(config: &Config, on: &str) -> (String, HashMap<String, String>) {
+    let mut overlay = HashMap::new();
+    let mut stripped = String::with_capacity(on.len());
+    for line in on.split_inclusive('\n') {
+        let trimmed = line.strip_suffix('\n').unwrap_or(line);
+        let trimmed = trimmed.strip_suffix('\r').unwrap_or(trimmed);
+        if let Ok(Some(captures)) = config.define_pattern.captures(trimmed) {
+            let name = captures
+                .name("name")
+                .expect("define_pattern must capture `name`")
+                .as_str();
+            let value = captures.name("value").map(|m| m.as_str()).unwrap_or("");
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            overlay.insert(name.to_string(), value.to_string());
+            continue;
+        }
+        stripped.push_str(line);
+    }
+    (stripped, overlay)
+}
+
+/// layers an in-memory overlay in front of another provider, so defines collected from
+/// a template take precedence over the provider it was substituted against.
+struct WithOverlay<'a, T> {
+    overlay: &'a HashMap<String, String>,
+    inner: &'a T,
+}
+
+impl<'a, T: ValueProvider> ValueProvider for WithOverlay<'a, T> {
+    fn get(&self, name: &str) -> Result<Cow<str>, Error> {
+        match self.overlay.get(name) {
+            Some(value) => Ok(Cow::Borrowed(value.as_str())),
+            None => self.inner.get(name),
         }
     }
 }
@@ -135,9 +435,8 @@ impl Env {
         char == '=' || char == '\0'
     }
 }
-impl ValueProivder for Env {
-    fn get(&self, name: impl AsRef<str>) -> Result<Cow<str>, Error> {
-        let name = name.as_ref();
+impl ValueProvider for Env {
+    fn get(&self, name: &str) -> Result<Cow<str>, Error> {
         if name.contains(Env::invalid_varible_name_pattern) {
             Err(Error::InvalidName)
         } else {
@@ -152,20 +451,44 @@ impl ValueProivder for Env {
     }
 }
 
-impl ValueProivder for HashMap<String, String> {
-    fn get(&self, name: impl AsRef<str>) -> Result<Cow<str>, Error> {
-        match self.get(name.as_ref()) {
+impl ValueProvider for HashMap<String, String> {
+    fn get(&self, name: &str) -> Result<Cow<str>, Error> {
+        match self.get(name) {
             Some(value) => Ok(Cow::Borrowed(value)),
             None => Err(Error::NotPresent),
         }
     }
 }
 
+/// looks up each provider in order, returning the first hit that isn't
+/// `Error::NotPresent`; any other error is propagated immediately without consulting
+/// later providers. Lets the common "in-memory overrides, falling back to the process
+/// environment" pattern compose out of existing [`ValueProvider`]s.
+pub struct Chain(Vec<Box<dyn ValueProvider>>);
+
+impl Chain {
+    pub fn new(providers: Vec<Box<dyn ValueProvider>>) -> Chain {
+        Chain(providers)
+    }
+}
+
+impl ValueProvider for Chain {
+    fn get(&self, name: &str) -> Result<Cow<str>, Error> {
+        for provider in &self.0 {
+            match provider.get(name) {
+                Err(Error::NotPresent) => continue,
+                result => return result,
+            }
+        }
+        Err(Error::NotPresent)
+    }
+}
+
 pub fn env(expand: &str) -> Result<Cow<str>, Error> {
     Env.substitute(&BASH, expand.as_ref())
 }
 
-// fn substitute(string: , impl ValueProivder) -> String {
+// fn substitute(string: , impl ValueProvider) -> String {
 //     if name.as_ref().contains(is_invalid_env_character) {
 //         Err(ProviderError::InvalidValue)
 //     } else {
@@ -174,9 +497,11 @@ pub fn env(expand: &str) -> Result<Cow<str>, Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use regex::RegexSet;
 
-    use crate::env;
+    use crate::{env, Config, ValueProvider, BASH};
 
     #[test]
     fn stuff() {
@@ -185,6 +510,183 @@ mod tests {
         assert_eq!(env("$PWD").expect("failed to expand").as_ref(), value);
     }
 
+    fn map() -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        map.insert("set".to_string(), "value".to_string());
+        map.insert("empty".to_string(), "".to_string());
+        map
+    }
+
+    #[test]
+    fn default_on_unset_or_empty() {
+        let map = map();
+        assert_eq!(map.substitute(&BASH, "${unset:-dflt}").unwrap(), "dflt");
+        assert_eq!(map.substitute(&BASH, "${empty:-dflt}").unwrap(), "dflt");
+        assert_eq!(map.substitute(&BASH, "${empty-dflt}").unwrap(), "");
+        assert_eq!(map.substitute(&BASH, "${set:-dflt}").unwrap(), "value");
+    }
+
+    #[test]
+    fn alternate_when_set() {
+        let map = map();
+        assert_eq!(map.substitute(&BASH, "${set:+alt}").unwrap(), "alt");
+        assert_eq!(map.substitute(&BASH, "${unset:+alt}").unwrap(), "");
+        assert_eq!(map.substitute(&BASH, "${empty:+alt}").unwrap(), "");
+        assert_eq!(map.substitute(&BASH, "${empty+alt}").unwrap(), "alt");
+    }
+
+    #[test]
+    fn required_surfaces_custom_message() {
+        let map = map();
+        let err = map
+            .substitute(&BASH, "${unset:?this variable is required}")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "this variable is required");
+        assert_eq!(map.substitute(&BASH, "${set:?unused}").unwrap(), "value");
+    }
+
+    #[test]
+    fn trim_and_replace() {
+        let mut map = map();
+        map.insert("path".to_string(), "foo/bar/baz.txt".to_string());
+        assert_eq!(map.substitute(&BASH, "${path#*/}").unwrap(), "bar/baz.txt");
+        assert_eq!(map.substitute(&BASH, "${path##*/}").unwrap(), "baz.txt");
+        assert_eq!(map.substitute(&BASH, "${path%.*}").unwrap(), "foo/bar/baz");
+        assert_eq!(map.substitute(&BASH, "${path/bar/qux}").unwrap(), "foo/qux/baz.txt");
+    }
+
+    #[test]
+    fn substring_and_length() {
+        let map = map();
+        assert_eq!(map.substitute(&BASH, "${set:1:3}").unwrap(), "alu");
+        assert_eq!(map.substitute(&BASH, "${#set}").unwrap(), "5");
+    }
+
+    #[test]
+    fn substring_length_does_not_overflow() {
+        let map = map();
+        // a length far past the value's end must clamp, not panic on `start + length`.
+        assert_eq!(
+            map.substitute(&BASH, "${set:1:9223372036854775807}").unwrap(),
+            "alue"
+        );
+    }
+
+    #[test]
+    fn nested_expansion() {
+        let mut map = map();
+        map.insert("b".to_string(), "fallback".to_string());
+        map.insert("inner".to_string(), "name".to_string());
+        map.insert("outer_name".to_string(), "resolved".to_string());
+
+        assert_eq!(map.substitute(&BASH, "${a:-$b}").unwrap(), "fallback");
+        assert_eq!(
+            map.substitute(&BASH, "${outer_${inner}}").unwrap(),
+            "resolved"
+        );
+    }
+
+    #[test]
+    fn arithmetic_expansion() {
+        let mut map = map();
+        map.insert("WIDTH".to_string(), "10".to_string());
+        assert_eq!(map.substitute(&BASH, "$(( WIDTH * 2 + 1 ))").unwrap(), "21");
+        assert_eq!(map.substitute(&BASH, "$((2 ** 10))").unwrap(), "1024");
+        assert_eq!(map.substitute(&BASH, "$((unset + 4))").unwrap(), "4");
+        assert!(map.substitute(&BASH, "$((1 / 0))").is_err());
+    }
+
+    #[test]
+    fn arithmetic_expansion_with_non_ascii_identifier() {
+        let map = map();
+        // a multi-byte identifier must not panic on a non-char-boundary byte index.
+        assert_eq!(map.substitute(&BASH, "$(( café + 1 ))").unwrap(), "1");
+    }
+
+    #[test]
+    fn chained_providers() {
+        let mut overrides = HashMap::new();
+        overrides.insert("set".to_string(), "override".to_string());
+        let mut fallback = HashMap::new();
+        fallback.insert("set".to_string(), "value".to_string());
+        fallback.insert("only_in_fallback".to_string(), "fb".to_string());
+
+        let chain = crate::Chain::new(vec![Box::new(overrides), Box::new(fallback)]);
+        assert_eq!(chain.substitute(&BASH, "${set}").unwrap(), "override");
+        assert_eq!(chain.substitute(&BASH, "${only_in_fallback}").unwrap(), "fb");
+        assert_eq!(chain.substitute(&BASH, "${missing:-dflt}").unwrap(), "dflt");
+    }
+
+    #[test]
+    fn inline_defines() {
+        let config = Config {
+            allow_inline_defines: true,
+            ..BASH.clone()
+        };
+        let map = map();
+        let template = "#define greeting = \"hi\"\n${greeting}, ${set}!";
+        assert_eq!(map.substitute(&config, template).unwrap(), "hi, value!");
+    }
+
+    #[test]
+    fn toml_dialect() {
+        let toml = r#"
+            [mustache]
+            open = "{{"
+            close = "}}"
+
+            [bash]
+            operators = true
+        "#;
+        let dialects = crate::dialect::load_dialects(toml).expect("valid dialect toml");
+
+        let map = map();
+        let mustache = &dialects["mustache"];
+        assert_eq!(map.substitute(mustache, "{{set}}").unwrap(), "value");
+
+        let bash = &dialects["bash"];
+        assert_eq!(map.substitute(bash, "${set:-dflt}").unwrap(), "value");
+    }
+
+    #[test]
+    fn match_table_replaces_literals_and_regex() {
+        use crate::match_table::{MatchTable, Pattern, Rule};
+
+        let rules = vec![
+            Rule {
+                pattern: Pattern::Literal("brb".to_string()),
+                replacement: "be right back".to_string(),
+            },
+            Rule {
+                pattern: Pattern::Regex(r"\bhi+\b".to_string()),
+                replacement: "hello, $set".to_string(),
+            },
+        ];
+        let table = MatchTable::compile(&rules).expect("valid rules");
+
+        let map = map();
+        assert_eq!(
+            table.replace_all(&map, "hiii, brb").unwrap(),
+            "hello, value, be right back"
+        );
+    }
+
+    #[test]
+    fn match_table_makes_progress_on_zero_width_match() {
+        use crate::match_table::{MatchTable, Pattern, Rule};
+
+        let rules = vec![Rule {
+            pattern: Pattern::Regex("a*".to_string()),
+            replacement: "X".to_string(),
+        }];
+        let table = MatchTable::compile(&rules).expect("valid rules");
+
+        let map = map();
+        // `a*` matches the empty string everywhere `a` doesn't; this must terminate
+        // rather than re-matching the same empty span at the same position forever.
+        assert_eq!(table.replace_all(&map, "bbb").unwrap(), "XbXbXb");
+    }
+
     #[test]
     fn regex_set() {
         // this is after split into words
@@ -0,0 +1,154 @@
+//! Fast multi-literal alias/trigger matching backed by `aho-corasick`.
+//!
+//! `Config`/`ValueProvider::substitute` is a good fit for templates with a handful of
+//! named variables, but a table of hundreds or thousands of fixed (pattern,
+//! replacement) rules - aliases, triggers, glossary entries - doesn't want a
+//! `fancy_regex` backtrack per match. [`MatchTable`] compiles literal rules into an
+//! `aho-corasick` automaton and regex rules into a `RegexSet`, then replaces every
+//! match across the whole rule set in a single leftmost-longest scan.
+
+use std::borrow::Cow;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::{Regex, RegexSet};
+use thiserror::Error;
+
+use crate::{ValueProvider, BASH};
+
+/// what a [`Rule`] matches against.
+pub enum Pattern {
+    /// matched verbatim, via the `aho-corasick` automaton.
+    Literal(String),
+    /// matched as a regex, via the `RegexSet`.
+    Regex(String),
+}
+
+/// one entry in a [`MatchTable`]. `replacement` may itself reference `$name` /
+/// `${name}` parameter expansions, resolved against the `ValueProvider` passed to
+/// [`MatchTable::replace_all`].
+pub struct Rule {
+    pub pattern: Pattern,
+    pub replacement: String,
+}
+
+#[derive(Error, Debug)]
+pub enum BuildError {
+    #[error("invalid literal pattern: {0}")]
+    AhoCorasick(#[from] aho_corasick::BuildError),
+    #[error("invalid regex pattern: {0}")]
+    Regex(#[from] regex::Error),
+}
+
+pub struct MatchTable {
+    literals: AhoCorasick,
+    literal_replacements: Vec<String>,
+    regex_set: RegexSet,
+    regexes: Vec<Regex>,
+    regex_replacements: Vec<String>,
+}
+
+impl MatchTable {
+    pub fn compile(rules: &[Rule]) -> Result<MatchTable, BuildError> {
+        let mut literal_patterns = Vec::new();
+        let mut literal_replacements = Vec::new();
+        let mut regex_patterns = Vec::new();
+        let mut regex_replacements = Vec::new();
+
+        for rule in rules {
+            match &rule.pattern {
+                Pattern::Literal(pattern) => {
+                    literal_patterns.push(pattern.clone());
+                    literal_replacements.push(rule.replacement.clone());
+                }
+                Pattern::Regex(pattern) => {
+                    regex_patterns.push(pattern.clone());
+                    regex_replacements.push(rule.replacement.clone());
+                }
+            }
+        }
+
+        let literals = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(&literal_patterns)?;
+        let regex_set = RegexSet::new(&regex_patterns)?;
+        let regexes = regex_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(MatchTable {
+            literals,
+            literal_replacements,
+            regex_set,
+            regexes,
+            regex_replacements,
+        })
+    }
+
+    /// scans `text` once, replacing every literal or regex match with its rule's
+    /// replacement. When a literal match and a regex match start at the same position,
+    /// the longer of the two wins, matching `aho-corasick`'s own leftmost-longest rule.
+    pub fn replace_all<T: ValueProvider>(&self, provider: &T, text: &str) -> Result<String, crate::Error> {
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+
+        while pos < text.len() {
+            let literal = self
+                .literals
+                .find(&text[pos..])
+                .map(|m| (m.start(), m.end(), m.pattern().as_usize()));
+
+            let regex = if self.regex_set.is_match(&text[pos..]) {
+                self.regexes
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, re)| re.find(&text[pos..]).map(|m| (m.start(), m.end(), i)))
+                    .min_by_key(|&(start, end, _)| (start, std::cmp::Reverse(end - start)))
+            } else {
+                None
+            };
+
+            let (start, end, replacement) = match (literal, regex) {
+                (Some((ls, le, li)), Some((rs, re, ri))) => {
+                    if (ls, std::cmp::Reverse(le - ls)) <= (rs, std::cmp::Reverse(re - rs)) {
+                        (ls, le, &self.literal_replacements[li])
+                    } else {
+                        (rs, re, &self.regex_replacements[ri])
+                    }
+                }
+                (Some((ls, le, li)), None) => (ls, le, &self.literal_replacements[li]),
+                (None, Some((rs, re, ri))) => (rs, re, &self.regex_replacements[ri]),
+                (None, None) => break,
+            };
+
+            out.push_str(&text[pos..pos + start]);
+            out.push_str(&expand_replacement(provider, replacement)?);
+
+            if end > start {
+                pos += end;
+            } else {
+                // a zero-width match (e.g. `a*`, `\b`) would otherwise re-match the
+                // same empty span at the same position forever; copy the next char
+                // verbatim and advance past it to guarantee progress.
+                let next = pos + start;
+                match text[next..].chars().next() {
+                    Some(c) => {
+                        out.push(c);
+                        pos = next + c.len_utf8();
+                    }
+                    None => pos = text.len(),
+                }
+            }
+        }
+
+        out.push_str(&text[pos..]);
+        Ok(out)
+    }
+}
+
+fn expand_replacement<'r, T: ValueProvider>(
+    provider: &T,
+    replacement: &'r str,
+) -> Result<Cow<'r, str>, crate::Error> {
+    provider.substitute(&BASH, replacement)
+}
@@ -0,0 +1,208 @@
+//! A small recursive-descent expander for the bash dialect.
+//!
+//! Unlike the flat `Config::pattern` regex used by the other dialects, bash parameter
+//! expansion nests: the word following `:-`/`:+`/etc. can itself contain `$name` or
+//! `${...}`, and (less commonly) so can the part before an operator. A single regex
+//! pass can't track brace depth, so this walks the input by hand: it finds each
+//! `${...}` body's matching brace, splits out the operator, and then recurses into the
+//! name and the argument.
+
+use crate::{expand_operator, Config, Error, OnNotPresent, ValueProvider};
+
+/// Operators recognized inside a brace group, longest-prefix-first so e.g. `:-` is
+/// matched before the bare `-` it contains.
+const OPERATORS: &[&str] = &[
+    ":-", ":+", ":?", "##", "#", "%%", "%", "//", "/", ":", "-", "+", "?",
+];
+
+pub(crate) fn expand<T: ValueProvider>(
+    provider: &T,
+    config: &Config,
+    input: &str,
+    errors: &mut Vec<Error>,
+) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(dollar) = rest.find('$') {
+        let escaped = dollar > 0 && rest.as_bytes()[dollar - 1] == b'\\';
+        out.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        if escaped {
+            out.push('$');
+            continue;
+        }
+
+        if let Some(body) = rest.strip_prefix("((") {
+            match find_arithmetic_end(body) {
+                Some((expr_end, consumed_end)) => {
+                    match crate::arithmetic::expand(provider, &body[..expr_end]) {
+                        Ok(value) => out.push_str(&value),
+                        Err(error) => errors.push(error),
+                    }
+                    rest = &body[consumed_end..];
+                }
+                None => {
+                    // no matching `))`: nothing to evaluate, leave it as-is.
+                    out.push_str("$((");
+                    rest = body;
+                }
+            }
+            continue;
+        }
+
+        if let Some(body) = rest.strip_prefix('{') {
+            match find_matching_brace(body) {
+                Some(end) => {
+                    out.push_str(&expand_brace(provider, config, &body[..end], errors));
+                    rest = &body[end + 1..];
+                }
+                None => {
+                    // unbalanced `${`: nothing to recurse into, leave it as-is.
+                    out.push_str("${");
+                    rest = body;
+                }
+            }
+            continue;
+        }
+
+        let name_len = rest
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        if name_len == 0 {
+            out.push('$');
+        } else {
+            out.push_str(&resolve(provider, config, &rest[..name_len], false, errors));
+            rest = &rest[name_len..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// finds the end of a `$(( ... ))` group. `input` is the text right after the `((` the
+/// caller already consumed, treated as having opened two parens. Returns
+/// `(expression_end, consumed_end)`: `input[..expression_end]` is the expression and
+/// `input[consumed_end..]` is what follows the closing `))`.
+fn find_arithmetic_end(input: &str) -> Option<(usize, usize)> {
+    let mut depth = 2i32;
+    let mut prev_close = None;
+    for (i, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((prev_close?, i + 1));
+                }
+                prev_close = Some(i);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// finds the byte offset of the `}` matching the opening brace already consumed by the caller.
+fn find_matching_brace(input: &str) -> Option<usize> {
+    let mut depth = 1i32;
+    for (i, c) in input.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// splits a brace group's inner text into `(name, operator, argument)`. The scan for an
+/// operator token stops descending into any nested `${...}` so an operator-looking
+/// character inside a nested expansion's argument isn't mistaken for the outer one.
+fn split_operator(inner: &str) -> (&str, Option<&'static str>, &str) {
+    let mut depth = 0i32;
+    let mut i = 0;
+    while i < inner.len() {
+        let c = inner[i..].chars().next().expect("i is a char boundary");
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 => {
+                if let Some(op) = OPERATORS.iter().find(|op| inner[i..].starts_with(**op)) {
+                    return (&inner[..i], Some(op), &inner[i + op.len()..]);
+                }
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    (inner, None, "")
+}
+
+fn expand_brace<T: ValueProvider>(
+    provider: &T,
+    config: &Config,
+    inner: &str,
+    errors: &mut Vec<Error>,
+) -> String {
+    if let Some(name) = inner.strip_prefix('#') {
+        if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return match provider.get(name) {
+                Ok(value) => value.chars().count().to_string(),
+                Err(Error::NotPresent) => "0".to_string(),
+                Err(error) => {
+                    errors.push(error);
+                    String::new()
+                }
+            };
+        }
+    }
+
+    let (name_part, op, arg_part) = split_operator(inner);
+    let name = expand(provider, config, name_part, errors);
+
+    match op {
+        None => resolve(provider, config, &name, true, errors),
+        Some(op) => {
+            let arg = expand(provider, config, arg_part, errors);
+            let value = provider.get(&name);
+            let (error, result) = expand_operator(config, value, op, &arg);
+            if let Some(error) = error {
+                errors.push(error);
+            }
+            result.into_owned()
+        }
+    }
+}
+
+/// looks up an already-resolved (no further `$` to expand) variable name.
+fn resolve<T: ValueProvider>(
+    provider: &T,
+    config: &Config,
+    name: &str,
+    braced: bool,
+    errors: &mut Vec<Error>,
+) -> String {
+    match provider.get(name) {
+        Ok(value) => value.into_owned(),
+        Err(Error::NotPresent) => match &config.on_not_present {
+            OnNotPresent::Error => {
+                errors.push(Error::NotPresent);
+                String::new()
+            }
+            OnNotPresent::Passthrough if braced => format!("${{{}}}", name),
+            OnNotPresent::Passthrough => format!("${}", name),
+            OnNotPresent::Default(default) => default.clone(),
+        },
+        Err(error) => {
+            errors.push(error);
+            String::new()
+        }
+    }
+}